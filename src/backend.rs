@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+use std::io::{stdout, Stdout, Write};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::{clear, color, cursor};
+
+/// Foreground colors used while drawing the field, food and snakes
+#[derive(Clone, Copy, PartialEq)]
+pub enum Color {
+    Default,
+    Red,
+    Yellow,
+    Blue,
+}
+
+/// Where `Game` draws to
+///
+/// Abstracts the terminal away so `Game` can be hosted on something other than the
+/// local TTY (e.g. a remote session), as long as the backend can place a colored
+/// character, clear the screen and flush what it buffered.
+pub trait RenderBackend {
+    /// Move the cursor to (x, y), set `color`, and write a single character
+    fn write_at(&mut self, x: u16, y: u16, c: char, color: Color);
+    /// Clear the whole screen and reset the cursor to the top-left corner
+    fn clear(&mut self);
+    /// Write raw text as-is, with no cursor positioning or color escapes
+    fn write_text(&mut self, s: &str);
+    /// Push any buffered output out to the underlying sink
+    fn flush(&mut self);
+}
+
+/// Write a single colored character, resetting the color right after so it never
+/// leaks into unrelated output written to the same sink afterwards
+fn write_colored<W: Write>(w: &mut W, x: u16, y: u16, c: char, color: Color) {
+    match color {
+        Color::Default => {
+            write!(w, "{}{}{}{}", cursor::Goto(x, y), color::Fg(color::Reset), c, color::Fg(color::Reset)).unwrap()
+        }
+        Color::Red => {
+            write!(w, "{}{}{}{}", cursor::Goto(x, y), color::Fg(color::Red), c, color::Fg(color::Reset)).unwrap()
+        }
+        Color::Yellow => {
+            write!(w, "{}{}{}{}", cursor::Goto(x, y), color::Fg(color::Yellow), c, color::Fg(color::Reset)).unwrap()
+        }
+        Color::Blue => {
+            write!(w, "{}{}{}{}", cursor::Goto(x, y), color::Fg(color::Blue), c, color::Fg(color::Reset)).unwrap()
+        }
+    }
+}
+
+/// Renders to the local terminal through termion, in raw mode
+pub struct TermionBackend {
+    stdout: RawTerminal<Stdout>,
+}
+impl TermionBackend {
+    pub fn new() -> TermionBackend {
+        TermionBackend {
+            stdout: stdout().into_raw_mode().unwrap(),
+        }
+    }
+}
+impl RenderBackend for TermionBackend {
+    fn write_at(&mut self, x: u16, y: u16, c: char, color: Color) {
+        write_colored(&mut self.stdout, x, y, c, color);
+    }
+
+    fn clear(&mut self) {
+        write!(self.stdout, "{}{}", clear::All, cursor::Goto(1, 1)).unwrap();
+    }
+
+    fn write_text(&mut self, s: &str) {
+        write!(self.stdout, "{}", s).unwrap();
+    }
+
+    fn flush(&mut self) {
+        self.stdout.flush().unwrap();
+    }
+}
+
+/// Renders into an in-memory buffer instead of a local terminal
+///
+/// Each `flush()` ships the bytes accumulated since the last flush down `frames` as one
+/// frame. Intended for hosting a game on a remote session (e.g. behind an SSH server):
+/// a network task owns the receiving end of the channel and forwards every frame it
+/// gets to the connected client.
+pub struct NetworkBackend {
+    buffer: Vec<u8>,
+    frames: Sender<Vec<u8>>,
+}
+impl NetworkBackend {
+    pub fn new(frames: Sender<Vec<u8>>) -> NetworkBackend {
+        NetworkBackend {
+            buffer: vec![],
+            frames,
+        }
+    }
+}
+impl RenderBackend for NetworkBackend {
+    fn write_at(&mut self, x: u16, y: u16, c: char, color: Color) {
+        write_colored(&mut self.buffer, x, y, c, color);
+    }
+
+    fn clear(&mut self) {
+        write!(self.buffer, "{}{}", clear::All, cursor::Goto(1, 1)).unwrap();
+    }
+
+    fn write_text(&mut self, s: &str) {
+        write!(self.buffer, "{}", s).unwrap();
+    }
+
+    fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let frame = std::mem::take(&mut self.buffer);
+        let _ = self.frames.send(frame);
+    }
+}
+
+/// Feeds `Game`'s key parser from bytes pushed in externally instead of a local TTY
+///
+/// Pairs with `NetworkBackend`: an SSH (or other remote) session pushes the raw bytes
+/// it receives from its client in via `push`, and `Game` drains them the same way it
+/// would drain its own local stdin.
+#[derive(Clone)]
+pub struct ChannelInput {
+    buffer: Arc<Mutex<VecDeque<u8>>>,
+}
+impl ChannelInput {
+    pub fn new() -> ChannelInput {
+        ChannelInput {
+            buffer: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Queue bytes received from the remote end for `Game` to consume
+    pub fn push(&self, data: &[u8]) {
+        self.buffer.lock().unwrap().extend(data);
+    }
+}
+impl Iterator for ChannelInput {
+    type Item = std::io::Result<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buffer.lock().unwrap().pop_front().map(Ok)
+    }
+}
@@ -1,9 +1,10 @@
+use crate::backend::{Color, RenderBackend, TermionBackend};
 use crate::{GameConfig};
-use std::io::{stdout, Stdout, Write, Read, Bytes};
+use std::collections::VecDeque;
+use std::io::{Read, Bytes};
 use std::fmt::{Display, Formatter, Result};
 use termion::event::{parse_event, Event, Key};
-use termion::raw::{IntoRawMode, RawTerminal};
-use termion::{async_stdin, clear, color, cursor, AsyncReader};
+use termion::{async_stdin, AsyncReader};
 use serde::{Deserialize, Serialize};
 
 // Char representing a border
@@ -56,33 +57,55 @@ pub enum GameState {
     Lost,
 }
 
-/// Game structure
-pub struct Game {
+/// Game structure, generic over where it renders to and where it reads raw key bytes
+/// from (defaults to the local terminal's own stdin)
+pub struct Game<B: RenderBackend, I: Iterator<Item = std::io::Result<u8>> = Bytes<AsyncReader>> {
     pub id: usize,
-    // Stdout in "raw" mode
-    stdout: RawTerminal<Stdout>,
-    // Asynchronous stdin to handle user inputs
-    stdin: Bytes<AsyncReader>,
+    backend: B,
+    // Raw byte stream `termion::event::parse_event` parses key events out of
+    input: I,
     pub snakes: Vec<Vec<Point>>,
     pub direction: Direction,
     pub food: Point,
     field: Vec<Vec<char>>,
     pub killed: bool,
+    // When true, `direction` is computed by the autopilot instead of read from the keyboard
+    pub bot_mode: bool,
+    // When true, draw a full plain-text frame of the board instead of ANSI cursor writes
+    plain_mode: bool,
+    // Last composed grid actually drawn, so `render` only touches cells that changed
+    shadow: Option<Vec<Vec<(char, Color)>>>,
+    // Sequence number of the last turn rendered, so an unchanged token skips the redraw
+    last_token: Option<u64>,
 }
-impl Game {
-    /// Create an empty game
-    pub fn empty() -> Game {
-        let stdout = stdout().into_raw_mode().unwrap();
-        let stdin = async_stdin().bytes();
+impl Game<TermionBackend, Bytes<AsyncReader>> {
+    /// Create an empty game rendering to the local terminal, reading keys from its stdin
+    pub fn empty() -> Game<TermionBackend, Bytes<AsyncReader>> {
+        Game::with_backend(TermionBackend::new())
+    }
+}
+impl<B: RenderBackend> Game<B, Bytes<AsyncReader>> {
+    /// Create an empty game rendering to `backend`, reading keys from the local stdin
+    pub fn with_backend(backend: B) -> Game<B, Bytes<AsyncReader>> {
+        Game::with_input(backend, async_stdin().bytes())
+    }
+}
+impl<B: RenderBackend, I: Iterator<Item = std::io::Result<u8>>> Game<B, I> {
+    /// Create an empty game rendering to `backend`, reading key bytes from `input`
+    pub fn with_input(backend: B, input: I) -> Game<B, I> {
         let game = Game {
             id: 0,
-            stdout: stdout,
-            stdin: stdin,
+            backend: backend,
+            input: input,
             direction: Direction::Right,
             field: vec![],
             snakes: vec![],
             food: Point { x: 0, y: 0 },
             killed: false,
+            bot_mode: false,
+            plain_mode: false,
+            shadow: None,
+            last_token: None,
         };
         return game;
     }
@@ -95,100 +118,124 @@ impl Game {
         self.food = config.food;
     }
 
+    /// Switch between ANSI cursor-positioned drawing and plain-text full-board frames
+    ///
+    /// Plain-text mode drops raw-mode/ANSI cursor escapes entirely so the board can be
+    /// read by a line-oriented client such as `nc`, which can't interpret them.
+    pub fn set_plain_mode(&mut self, enabled: bool) {
+        self.plain_mode = enabled;
+    }
+
+    /// Compose the whole board (borders, food, snakes) as a grid of (char, color) cells
+    fn composed_grid(&self) -> Vec<Vec<(char, Color)>> {
+        let mut grid: Vec<Vec<(char, Color)>> = self
+            .field
+            .iter()
+            .map(|line| line.iter().map(|c| (*c, field_color(*c))).collect())
+            .collect();
+        place_on_grid(&mut grid, &self.food, FOOD_CHAR, Color::Red);
+        for (id, snake) in self.snakes.iter().enumerate() {
+            let color = if id == self.id { Color::Red } else { Color::Yellow };
+            for p in snake.iter() {
+                place_on_grid(&mut grid, p, SNAKE_CHAR, color);
+            }
+        }
+        grid
+    }
+
+    /// Compose the whole board (borders, food, snakes) as plain text, one line per row
+    #[allow(clippy::inherent_to_string)] // intentionally not `Display`: plain_mode needs an owned String to send as one frame
+    pub fn to_string(&self) -> String {
+        grid_to_string(&self.composed_grid())
+    }
+
     /// Draw the game's borders
     pub fn draw_field(&mut self) {
-        // On écrit dans notre console statique dans l'ordre
-        // - on efface tout le contenu
-        // - place le curseur au début de la première ligne
-        // - la couleur du ForeGround choisie est bleu
-        write!(
-            self.stdout,
-            "{}{}{}",
-            clear::All,
-            cursor::Goto(1, 1),
-            color::Fg(color::Blue)
-        )
-        .unwrap();
-        // On appelle flush() pour forcer les modifications dans
-        // stdout
-        self.stdout.flush().unwrap();
-
-        // Affichage de l'espace de jeu
-        let mut i = 0;
-        for line in self.field.iter() {
-            for c in line.into_iter() {
-                write!(self.stdout, "{}", c).unwrap();
+        // In plain-text mode the whole board, borders included, is sent by `draw_snakes`
+        if self.plain_mode {
+            return;
+        }
+        self.backend.clear();
+        for (y, line) in self.field.iter().enumerate() {
+            for (x, c) in line.iter().enumerate() {
+                self.backend.write_at(x as u16 + 1, y as u16 + 1, *c, field_color(*c));
             }
-            // Passe à la ligne suivante et replace le curseur en début de ligne
-            write!(self.stdout, "{}\n", cursor::Goto(1, (i + 1) as u16)).unwrap();
-            i += 1;
         }
-
-        // Remet à jour la couleur utilisé
-        write!(self.stdout, "{}", color::Fg(color::Reset)).unwrap();
-        self.stdout.flush().unwrap();
+        self.backend.flush();
     }
 
     /// Draw the food
     pub fn draw_food(&mut self) {
-        // 4 étapes
-        // - place le curseur à la position souhaitée
-        // - choisit une couleur pour la pomme
-        // - écrit le caractère correspondant à la pomme
-        // - remet à zéro la couleur pour les prochaines utilisations
-        write!(
-            self.stdout,
-            "{}{}{}{}",
-            cursor::Goto(self.food.x, self.food.y),
-            color::Fg(color::Red),
-            FOOD_CHAR,
-            color::Fg(color::Reset)
-        )
-        .unwrap();
-        self.stdout.flush().unwrap();
+        // In plain-text mode the food is sent as part of `draw_snakes`'s full frame
+        if self.plain_mode {
+            return;
+        }
+        self.backend.write_at(self.food.x, self.food.y, FOOD_CHAR, Color::Red);
+        self.backend.flush();
     }
 
-    /// Draw snake using char c
-    /// (if c = ' ' it will remove it from the screen)
-    fn draw_snake_with_char(&mut self, c: char, snake: Vec<Point>, own: bool) {
-        // Select color
-        if own {
-            write!(self.stdout, "{}", color::Fg(color::Red)).unwrap();
+    /// Draw snakes using SNAKE_CHAR, then remember the drawn grid so that `render` can
+    /// diff against it for the first turn instead of redrawing everything again
+    pub fn draw_snakes(&mut self) {
+        let grid = self.composed_grid();
+        if self.plain_mode {
+            self.backend.write_text(&grid_to_string(&grid));
+            self.backend.flush();
         } else {
-            write!(self.stdout, "{}", color::Fg(color::Yellow)).unwrap();
-        }
-        self.stdout.flush().unwrap();
-        // Add snake
-        for p in snake.iter() {
-            write!(
-                self.stdout,
-                "{}{}",
-                cursor::Goto(p.x, p.y),
-                c,
-            ).unwrap();
+            for (y, line) in grid.iter().enumerate() {
+                for (x, &(c, color)) in line.iter().enumerate() {
+                    if c != ' ' {
+                        self.backend.write_at(x as u16 + 1, y as u16 + 1, c, color);
+                    }
+                }
+            }
+            self.backend.flush();
         }
-        // Reset color
-        write!(
-            self.stdout,
-            "{}{}",
-            cursor::Goto(0, self.field.len() as u16 + 1),
-            color::Fg(color::Reset)
-        ).unwrap();
-        self.stdout.flush().unwrap();
+        self.shadow = Some(grid);
     }
 
-    /// Draw snakes using SNAKE_CHAR
-    pub fn draw_snakes(&mut self) {
-        for id in 0..self.snakes.len() {
-            self.draw_snake_with_char(SNAKE_CHAR, self.snakes[id].clone(), self.id == id);
+    /// Render the turn identified by `token`
+    ///
+    /// Skips the redraw entirely when `token` matches the last one rendered (the server
+    /// didn't actually change anything). Otherwise composes the board and, in ANSI mode,
+    /// writes only the cells that differ from the last drawn grid instead of clearing and
+    /// redrawing the whole board, which is what caused the flicker.
+    pub fn render(&mut self, token: u64) {
+        if self.last_token == Some(token) {
+            return;
         }
-    }
+        self.last_token = Some(token);
 
-    /// Clear snakes
-    pub fn clear_snakes(&mut self) {
-        for id in 0..self.snakes.len() {
-            self.draw_snake_with_char(' ', self.snakes[id].clone(), false);
+        let grid = self.composed_grid();
+        if self.plain_mode {
+            self.backend.write_text(&grid_to_string(&grid));
+            self.backend.flush();
+            self.shadow = Some(grid);
+            return;
         }
+
+        match self.shadow.take() {
+            Some(shadow) => {
+                for (y, line) in grid.iter().enumerate() {
+                    for (x, cell) in line.iter().enumerate() {
+                        if shadow[y][x] != *cell {
+                            let &(c, color) = cell;
+                            self.backend.write_at(x as u16 + 1, y as u16 + 1, c, color);
+                        }
+                    }
+                }
+            }
+            None => {
+                self.backend.clear();
+                for (y, line) in grid.iter().enumerate() {
+                    for (x, &(c, color)) in line.iter().enumerate() {
+                        self.backend.write_at(x as u16 + 1, y as u16 + 1, c, color);
+                    }
+                }
+            }
+        }
+        self.backend.flush();
+        self.shadow = Some(grid);
     }
 
     /// Update snakes positions
@@ -204,9 +251,9 @@ impl Game {
     fn get_last_key_event(&mut self) -> Option<Event> {
         let mut prev: Option<Event> = None;
         loop {
-            match self.stdin.next() {
+            match self.input.next() {
                 Some(b) => {
-                    match parse_event(b.unwrap(), &mut self.stdin) {
+                    match parse_event(b.unwrap(), &mut self.input) {
                         Ok(e) => prev = Some(e),
                         _ => (),
                     }
@@ -227,10 +274,11 @@ impl Game {
                 match e {
                     Event::Key(key) => {
                         match key {
-                            Key::Up => self.direction = Direction::Up,
-                            Key::Down => self.direction = Direction::Down,
-                            Key::Left => self.direction = Direction::Left,
-                            Key::Right => self.direction = Direction::Right,
+                            Key::Up if !self.bot_mode => self.direction = Direction::Up,
+                            Key::Down if !self.bot_mode => self.direction = Direction::Down,
+                            Key::Left if !self.bot_mode => self.direction = Direction::Left,
+                            Key::Right if !self.bot_mode => self.direction = Direction::Right,
+                            Key::Char('a') => self.bot_mode = !self.bot_mode,
                             Key::Char('q') => self.killed = true,
                             _ => ()
                         }
@@ -240,6 +288,140 @@ impl Game {
             },
             None => ()
         }
+        // Let the autopilot pick the next direction instead of the keyboard
+        if self.bot_mode {
+            self.compute_bot_direction();
+        }
+    }
+
+    /// Compute the next direction for the autopilot
+    ///
+    /// Runs a breadth-first search from the player's head to the food and takes its first
+    /// step, but only if a flood-fill from the resulting cell reaches at least as many free
+    /// cells as the snake is long (otherwise the snake would trap itself). If no such step
+    /// exists, falls back to the legal neighbor with the largest flood-fill.
+    fn compute_bot_direction(&mut self) {
+        let head = self.snakes[self.id][0].clone();
+        let blocked = self.build_blocked();
+        let snake_len = self.snakes[self.id].len();
+        let head_neighbors = self.neighbors(&head, &blocked);
+
+        if let Some(next) = self.bfs_path_to_food(&head, &blocked) {
+            if self.flood_fill_count(&next, &blocked) >= snake_len {
+                self.direction = direction_between(&head, &next);
+                return;
+            }
+        }
+
+        let mut best: Option<(Point, usize)> = None;
+        for n in head_neighbors.into_iter() {
+            let reachable = self.flood_fill_count(&n, &blocked);
+            if best.as_ref().is_none_or(|(_, count)| reachable > *count) {
+                best = Some((n, reachable));
+            }
+        }
+        if let Some((n, _)) = best {
+            self.direction = direction_between(&head, &n);
+        }
+    }
+
+    /// Mark every cell occupied by a snake body or a border as blocked
+    fn build_blocked(&self) -> Vec<Vec<bool>> {
+        let mut blocked = vec![vec![false; self.field[0].len()]; self.field.len()];
+        for (y, line) in self.field.iter().enumerate() {
+            for (x, c) in line.iter().enumerate() {
+                blocked[y][x] = *c == BORDER_CHAR;
+            }
+        }
+        for snake in self.snakes.iter() {
+            for p in snake.iter() {
+                // `Point` is 1-indexed (see `place_on_grid`), `blocked` is a plain 0-indexed array
+                blocked[(p.y - 1) as usize][(p.x - 1) as usize] = true;
+            }
+        }
+        blocked
+    }
+
+    /// Free, in-bounds neighbors of a cell
+    fn neighbors(&self, p: &Point, blocked: &[Vec<bool>]) -> Vec<Point> {
+        let height = blocked.len() as u16;
+        let width = blocked[0].len() as u16;
+        let mut candidates = vec![];
+        if p.y > 0 {
+            candidates.push(Point { x: p.x, y: p.y - 1 });
+        }
+        if p.y + 1 < height {
+            candidates.push(Point { x: p.x, y: p.y + 1 });
+        }
+        if p.x > 0 {
+            candidates.push(Point { x: p.x - 1, y: p.y });
+        }
+        if p.x + 1 < width {
+            candidates.push(Point { x: p.x + 1, y: p.y });
+        }
+        candidates
+            .into_iter()
+            .filter(|n| !blocked[(n.y - 1) as usize][(n.x - 1) as usize])
+            .collect()
+    }
+
+    /// Breadth-first search from `start` to `self.food`, returning the first step taken
+    fn bfs_path_to_food(&self, start: &Point, blocked: &[Vec<bool>]) -> Option<Point> {
+        let height = blocked.len();
+        let width = blocked[0].len();
+        let mut visited = vec![vec![false; width]; height];
+        // First step taken to reach each visited cell, so the move out of `start` is known
+        // as soon as the food is reached, without walking the path back
+        let mut first_step: Vec<Vec<Option<Point>>> = vec![vec![None; width]; height];
+        visited[(start.y - 1) as usize][(start.x - 1) as usize] = true;
+
+        let mut queue = VecDeque::new();
+        queue.push_back(start.clone());
+        while let Some(p) = queue.pop_front() {
+            if p.x == self.food.x && p.y == self.food.y {
+                return first_step[(p.y - 1) as usize][(p.x - 1) as usize].clone();
+            }
+            for n in self.neighbors(&p, blocked).into_iter() {
+                if visited[(n.y - 1) as usize][(n.x - 1) as usize] {
+                    continue;
+                }
+                visited[(n.y - 1) as usize][(n.x - 1) as usize] = true;
+                let step = if p.x == start.x && p.y == start.y {
+                    n.clone()
+                } else {
+                    first_step[(p.y - 1) as usize][(p.x - 1) as usize].clone().unwrap()
+                };
+                first_step[(n.y - 1) as usize][(n.x - 1) as usize] = Some(step);
+                queue.push_back(n);
+            }
+        }
+        None
+    }
+
+    /// Count cells reachable from `start` without crossing a blocked cell
+    fn flood_fill_count(&self, start: &Point, blocked: &[Vec<bool>]) -> usize {
+        if blocked[(start.y - 1) as usize][(start.x - 1) as usize] {
+            return 0;
+        }
+        let height = blocked.len();
+        let width = blocked[0].len();
+        let mut visited = vec![vec![false; width]; height];
+        visited[(start.y - 1) as usize][(start.x - 1) as usize] = true;
+
+        let mut queue = VecDeque::new();
+        queue.push_back(start.clone());
+        let mut count = 0;
+        while let Some(p) = queue.pop_front() {
+            count += 1;
+            for n in self.neighbors(&p, blocked).into_iter() {
+                if visited[(n.y - 1) as usize][(n.x - 1) as usize] {
+                    continue;
+                }
+                visited[(n.y - 1) as usize][(n.x - 1) as usize] = true;
+                queue.push_back(n);
+            }
+        }
+        count
     }
 
     /// Handle force start event to start the game with less than 4 players
@@ -264,6 +446,44 @@ impl Game {
     }
 }
 
+/// Direction to move from `from` to reach the adjacent cell `to`
+fn direction_between(from: &Point, to: &Point) -> Direction {
+    if to.y < from.y {
+        Direction::Up
+    } else if to.y > from.y {
+        Direction::Down
+    } else if to.x < from.x {
+        Direction::Left
+    } else {
+        Direction::Right
+    }
+}
+
+/// Color a field character is drawn with before food or snakes are placed on top of it
+fn field_color(c: char) -> Color {
+    if c == BORDER_CHAR {
+        Color::Blue
+    } else {
+        Color::Default
+    }
+}
+
+/// Place a single colored character on `grid` at `p`'s (1-based) coordinates
+fn place_on_grid(grid: &mut [Vec<(char, Color)>], p: &Point, c: char, color: Color) {
+    let (x, y) = (p.x as usize, p.y as usize);
+    if y >= 1 && y <= grid.len() && x >= 1 && x <= grid[0].len() {
+        grid[y - 1][x - 1] = (c, color);
+    }
+}
+
+/// Flatten a composed grid into plain text, one line per row, with no color information
+fn grid_to_string(grid: &[Vec<(char, Color)>]) -> String {
+    grid.iter()
+        .map(|line| line.iter().map(|(c, _)| *c).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
 /// Init the game's field
 /// Draw field's borders and put an empty char otherwise
 pub fn init_field(width: usize, height: usize) -> Vec<Vec<char>> {
@@ -291,3 +511,26 @@ pub fn init_field(width: usize, height: usize) -> Vec<Vec<char>> {
 
     return field;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::NetworkBackend;
+
+    fn test_game() -> Game<NetworkBackend, std::iter::Empty<std::io::Result<u8>>> {
+        let (frames_tx, _frames_rx) = std::sync::mpsc::channel();
+        Game::with_input(NetworkBackend::new(frames_tx), std::iter::empty())
+    }
+
+    #[test]
+    fn build_blocked_marks_the_array_aligned_cell_for_a_snake_body_point() {
+        let mut game = test_game();
+        game.field = init_field(7, 7);
+        game.snakes = vec![vec![Point { x: 3, y: 3 }]];
+
+        let blocked = game.build_blocked();
+
+        assert!(blocked[2][2], "snake body at Point{{x:3,y:3}} should block array cell [2][2]");
+        assert!(!blocked[3][3], "raw (1-indexed) coordinates should not be used as the array index");
+    }
+}
@@ -1,19 +1,58 @@
+pub mod backend;
 pub mod game;
+pub mod ssh_server;
+use backend::RenderBackend;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
 use chrono::{Timelike, Utc};
+use rand::{rng, Rng};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::net::TcpStream;
+use std::io::Write;
+use std::net::UdpSocket;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 
 const SERVER_ADDR: &'static str = "127.0.0.1";
 const SERVER_PORT: usize = 8080;
+const SSH_BIND_ADDR: &'static str = "0.0.0.0:2222";
 
 const LOG_FILE: &'static str = "log";
 
-/// Stream object to store our reader and writer object
-struct Stream<'a> {
-    reader: BufReader<&'a TcpStream>,
-    writer: BufWriter<&'a TcpStream>,
+// Shared secret the session key is derived from, combined with a per-connection
+// handshake nonce so the same key is never reused across connections. In a real
+// deployment this would be provisioned out of band rather than baked into the binary.
+const PRESHARED_KEY: [u8; 32] = [
+    0x1f, 0x3e, 0x5d, 0x7c, 0x9b, 0xba, 0xd9, 0xf8, 0x17, 0x36, 0x55, 0x74, 0x93, 0xb2, 0xd1, 0xf0,
+    0x0e, 0x2d, 0x4c, 0x6b, 0x8a, 0xa9, 0xc8, 0xe7, 0x06, 0x25, 0x44, 0x63, 0x82, 0xa1, 0xc0, 0xdf,
+];
+const HANDSHAKE_NONCE_LEN: usize = 16;
+const FRAME_NONCE_LEN: usize = 12;
+
+// Generous upper bound on a single frame's ciphertext, well above anything a real board
+// state or handshake message could serialize to. The length prefix arrives before the
+// authentication tag is checked, so it's untrusted; without this cap a corrupted or
+// hostile peer could claim a multi-gigabyte frame and force a huge allocation.
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+const DISCOVERY_PORT: u16 = 8081;
+const DISCOVERY_MAGIC: &[u8] = b"SNAKE_DISCOVER";
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// How often the main task samples the keyboard while waiting on the server
+const INPUT_TICK: Duration = Duration::from_millis(30);
+const INBOX_CAPACITY: usize = 32;
+const OUTBOX_CAPACITY: usize = 32;
+
+/// A server that answered a discovery broadcast
+#[derive(Deserialize)]
+struct ServerInfo {
+    address: String,
+    players: usize,
+    flags: Vec<String>,
 }
 
 /// Game configuration
@@ -34,6 +73,8 @@ struct DirectionMessage {
 #[derive(Deserialize)]
 struct TurnMessage {
     id: usize,
+    // Sequence number of this turn, so the client can skip redrawing an unchanged one
+    token: u64,
     food: game::Point,
     snakes: Vec<Vec<game::Point>>,
 }
@@ -58,32 +99,132 @@ pub struct ForceStartMessage {
     pub force_start: bool,
 }
 
-/// Initialize a connection with the server
-fn connect<'a>() -> Result<TcpStream, String> {
-    let addr = format!("{}:{}", SERVER_ADDR, SERVER_PORT);
-    return match TcpStream::connect(addr) {
-        Ok(stream) => Ok(stream),
-        Err(e) => Err(format!("Failed to connect to server: {}", e)),
-    };
+/// Messages the network-read task decodes off the wire and drops into the inbox.
+///
+/// `serde(untagged)` lets one decode attempt pick the right variant from shape alone,
+/// since each of the four message bodies has a required field none of the others has.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ServerMessage {
+    Config(GameConfig),
+    Turn(TurnMessage),
+    State(StateMessage),
+    Event(EventMessage),
+}
+
+/// Messages the main task drops into the outbox for the write task to flush
+enum OutMessage {
+    Direction(DirectionMessage),
+    ForceStart(ForceStartMessage),
+}
+impl OutMessage {
+    fn to_json(&self) -> String {
+        match self {
+            OutMessage::Direction(m) => serde_json::to_string(m).unwrap(),
+            OutMessage::ForceStart(m) => serde_json::to_string(m).unwrap(),
+        }
+    }
+}
+
+/// Broadcast a discovery packet on the LAN and collect replies from listening servers
+fn discover_servers() -> Vec<ServerInfo> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).unwrap();
+    socket.set_broadcast(true).unwrap();
+    socket.set_read_timeout(Some(DISCOVERY_TIMEOUT)).unwrap();
+    socket
+        .send_to(DISCOVERY_MAGIC, ("255.255.255.255", DISCOVERY_PORT))
+        .unwrap();
+
+    let mut servers = vec![];
+    let mut buf = [0u8; 512];
+    // The read timeout above bounds how long we wait for replies to trickle in
+    while let Ok((n, _)) = socket.recv_from(&mut buf) {
+        if let Ok(info) = serde_json::from_slice::<ServerInfo>(&buf[..n]) {
+            servers.push(info);
+        }
+    }
+    servers
+}
+
+/// Let the user interactively pick a discovered server, falling back to the hardcoded
+/// `SERVER_ADDR`/`SERVER_PORT` when discovery found nothing
+fn choose_server(servers: &[ServerInfo]) -> String {
+    if servers.is_empty() {
+        return format!("{}:{}", SERVER_ADDR, SERVER_PORT);
+    }
+    println!("Discovered servers:");
+    for (i, server) in servers.iter().enumerate() {
+        println!("  {}) {} - {} player(s) {:?}", i + 1, server.address, server.players, server.flags);
+    }
+    println!("Pick a server by number (ENTER for the first one):");
+    let mut choice = String::new();
+    std::io::stdin().read_line(&mut choice).unwrap();
+    let index = choice.trim().parse::<usize>().unwrap_or(1).saturating_sub(1);
+    servers.get(index).unwrap_or(&servers[0]).address.clone()
 }
 
-/// Serialize object and send it as a json to the server
-fn send<T>(stream: &mut Stream, object: T)
+/// Exchange a handshake nonce with the server and derive the session key from it and
+/// the pre-shared key, so every connection ends up with its own ChaCha20-Poly1305 key
+async fn handshake(stream: &mut TcpStream) -> ChaCha20Poly1305 {
+    let mut client_nonce = [0u8; HANDSHAKE_NONCE_LEN];
+    rng().fill_bytes(&mut client_nonce);
+    stream.write_all(&client_nonce).await.unwrap();
+    stream.flush().await.unwrap();
+
+    let mut server_nonce = [0u8; HANDSHAKE_NONCE_LEN];
+    stream.read_exact(&mut server_nonce).await.unwrap();
+
+    let mut hasher = Sha256::new();
+    hasher.update(PRESHARED_KEY);
+    hasher.update(client_nonce);
+    hasher.update(server_nonce);
+    let session_key = hasher.finalize();
+    ChaCha20Poly1305::new(Key::from_slice(&session_key))
+}
+
+/// Seal an already-serialized payload with ChaCha20-Poly1305 and send it length-prefixed and
+/// framed as `[u32 LE length][12-byte nonce][ciphertext+tag]`
+async fn send<W>(writer: &mut W, cipher: &ChaCha20Poly1305, payload: &str)
 where
-    T: Serialize,
+    W: AsyncWrite + Unpin,
 {
-    let payload = format!("{}\n", serde_json::to_string(&object).unwrap());
-    stream.writer.write(payload.as_bytes()).unwrap();
-    stream.writer.flush().unwrap();
+    let mut nonce_bytes = [0u8; FRAME_NONCE_LEN];
+    rng().fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+    let ciphertext = cipher.encrypt(nonce, payload.as_bytes()).unwrap();
+
+    let mut frame = Vec::with_capacity(FRAME_NONCE_LEN + ciphertext.len());
+    frame.extend_from_slice(&nonce_bytes);
+    frame.extend_from_slice(&ciphertext);
+
+    writer.write_all(&(frame.len() as u32).to_le_bytes()).await.unwrap();
+    writer.write_all(&frame).await.unwrap();
+    writer.flush().await.unwrap();
 }
 
-/// Wait for server message, read it and deserialize it depeding on T
-fn receive<'a, T>(stream: &mut Stream, response: &'a mut String) -> T
+/// Wait for a server frame, verify and decrypt it, then deserialize it as T
+async fn receive<R, T>(reader: &mut R, cipher: &ChaCha20Poly1305) -> T
 where
-    T: Deserialize<'a>,
+    R: AsyncRead + Unpin,
+    T: for<'de> Deserialize<'de>,
 {
-    stream.reader.read_line(response).unwrap();
-    serde_json::from_str::<'a, T>(&response[..]).unwrap()
+    let mut len_bytes = [0u8; 4];
+    reader.read_exact(&mut len_bytes).await.unwrap();
+    let len = u32::from_le_bytes(len_bytes) as usize;
+    if len > MAX_FRAME_LEN {
+        panic!("Frame length {} exceeds maximum of {}", len, MAX_FRAME_LEN);
+    }
+
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await.unwrap();
+    let (nonce_bytes, ciphertext) = frame.split_at(FRAME_NONCE_LEN);
+    let nonce = Nonce::from_slice(nonce_bytes);
+    let plaintext = cipher.decrypt(nonce, ciphertext).unwrap();
+
+    let text = String::from_utf8(plaintext).unwrap();
+    let object = serde_json::from_str(&text).unwrap();
+    log(&format!("Received: {}", text)[..]);
+    object
 }
 
 /// Log function
@@ -95,42 +236,78 @@ fn log(s: &str) {
     }
 }
 
-fn main() {
-    // Reset log file
-    File::create(LOG_FILE).unwrap();
-
-    // Connect to the server
-    let s = connect().unwrap();
-    let mut stream = Stream {
-        reader: BufReader::new(&s),
-        writer: BufWriter::new(&s),
-    };
+/// Connect to the snake server at `addr`, negotiate a session key, then play `game` to
+/// completion. Generic over both the render backend and the key input source so the
+/// same connection/protocol logic drives a local terminal session as well as a session
+/// hosted over something else entirely (e.g. an SSH channel, see `ssh_server`).
+pub async fn play<B, I>(addr: &str, mut game: game::Game<B, I>)
+where
+    B: RenderBackend,
+    I: Iterator<Item = std::io::Result<u8>>,
+{
+    // Connect to the server and negotiate a session key
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let cipher = handshake(&mut stream).await;
     log("Connection initialized successfully");
 
-    let mut game = game::Game::empty();
+    // From here on, reading and writing each run on their own task and the main task
+    // samples the keyboard on a steady tick, so keystrokes are sampled continuously
+    // instead of once per server round-trip. The
+    // read task feeds decoded messages into `inbox`, the main task feeds outgoing ones
+    // into `outbox`, and the write task just flushes whatever lands there.
+    let (reader, mut writer) = stream.into_split();
+    let (inbox_tx, mut inbox_rx) = mpsc::channel::<ServerMessage>(INBOX_CAPACITY);
+    let (outbox_tx, mut outbox_rx) = mpsc::channel::<OutMessage>(OUTBOX_CAPACITY);
+
+    let read_cipher = cipher.clone();
+    tokio::spawn(async move {
+        let mut reader = reader;
+        loop {
+            let message: ServerMessage = receive(&mut reader, &read_cipher).await;
+            if inbox_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(message) = outbox_rx.recv().await {
+            send(&mut writer, &cipher, &message.to_json()).await;
+        }
+    });
 
     // Enter lobby
     log("Entering Lobby");
     println!("Press ENTER to start game with less than 4 players");
     loop {
-        let mut response = String::new();
-        let event: EventMessage = receive(&mut stream, &mut response);
-        match event.event {
-            game::GameEvent::Start => break,
-            game::GameEvent::WaitInLobby => (),
-            // If it's not a new turn something went wrong, so exit game
+        match inbox_rx.recv().await {
+            Some(ServerMessage::Event(event)) => match event.event {
+                game::GameEvent::Start => break,
+                game::GameEvent::WaitInLobby => {
+                    // If force start game, send start message to the server
+                    let force_start = game.force_start();
+                    if outbox_tx.send(OutMessage::ForceStart(ForceStartMessage { force_start })).await.is_err() {
+                        panic!("Connection to server lost");
+                    }
+                }
+                // If it's not a lobby event something went wrong, so exit game
+                _ => panic!("Wrong server message received"),
+            },
+            // Network read task ended, connection closed before the game even started
+            None => return,
             _ => panic!("Wrong server message received"),
         }
-        // If force start game, send start message to the server
-        send(&mut stream, ForceStartMessage { force_start: game.force_start() });
     }
 
     log("Starting game");
 
     // Read GameConfig from the server
-    let mut response = String::new();
-    let config: GameConfig = receive(&mut stream, &mut response);
-    log(&format!("Received game configuration: {}", response)[..]);
+    let config = match inbox_rx.recv().await {
+        Some(ServerMessage::Config(config)) => config,
+        // Network read task ended, connection closed before the config arrived
+        None => return,
+        _ => panic!("Wrong server message received"),
+    };
 
     // Init game
     log("Initializing game");
@@ -140,48 +317,84 @@ fn main() {
     game.draw_snakes();
 
     // Enter play state
+    let mut tick = tokio::time::interval(INPUT_TICK);
     loop {
-        // Wait new turn event before making this call, it allows a better sync with the server
-        let mut response = String::new();
-        let event: EventMessage = receive(&mut stream, &mut response);
-        match event.event {
-            game::GameEvent::NewTurn => (),
-            _ => break, // If it's not a new turn something went wrong, so exit game
-        }
-        // Handle user inputs
-        game.handle_input();
-        // If user killed the game, exit
-        if game.killed {
-            break;
-        }
-        log(&format!("Current direction: {}", game.direction.clone())[..]);
-        // Send current direction to the server
-        log("Send user direction to the server");
-        send(
-            &mut stream,
-            DirectionMessage {
-                direction: game.direction.clone(),
-            },
-        );
-        // Wait server response with updated game
-        let mut response = String::new();
-        let turn: TurnMessage = receive(&mut stream, &mut response);
-        log(&format!("Received next turn data: {}", response)[..]);
-        // Clear old snake positions and update new ones
-        game.id = turn.id;
-        game.food = turn.food;
-        game.clear_snakes();
-        game.draw_food();
-        game.update(turn.snakes);
-        game.draw_snakes();
-        // Check if the game is over
-        response = String::new();
-        let state: StateMessage = receive(&mut stream, &mut response);
-        log(&format!("Received game state: {}", response)[..]);
-        if state.state == game::GameState::Lost {
-            println!("You lose");
-            log("You lose");
-            break;
+        tokio::select! {
+            message = inbox_rx.recv() => {
+                match message {
+                    Some(ServerMessage::Event(event)) => {
+                        match event.event {
+                            game::GameEvent::NewTurn => (),
+                            // If it's not a new turn something went wrong, so exit game
+                            _ => break,
+                        }
+                        if game.killed {
+                            break;
+                        }
+                        log(&format!("Current direction: {}", game.direction.clone())[..]);
+                        log("Send user direction to the server");
+                        let direction = game.direction.clone();
+                        if outbox_tx.send(OutMessage::Direction(DirectionMessage { direction })).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(ServerMessage::Turn(turn)) => {
+                        log("Received next turn data");
+                        game.id = turn.id;
+                        game.food = turn.food;
+                        game.update(turn.snakes);
+                        game.render(turn.token);
+                    }
+                    Some(ServerMessage::State(state)) => {
+                        log("Received game state");
+                        if state.state == game::GameState::Lost {
+                            println!("You lose");
+                            log("You lose");
+                            break;
+                        }
+                    }
+                    None => break, // Network read task ended, connection closed
+                    // A GameConfig only ever arrives once, right after the lobby
+                    _ => panic!("Wrong server message received"),
+                }
+            }
+            _ = tick.tick() => {
+                // Keep sampling the keyboard between server messages so input never
+                // waits on a round-trip
+                game.handle_input();
+                if game.killed {
+                    break;
+                }
+            }
         }
     }
 }
+
+#[tokio::main]
+async fn main() {
+    // Reset log file
+    File::create(LOG_FILE).unwrap();
+
+    // `--serve-ssh [bind addr]` hosts the client behind an SSH server instead of
+    // playing locally, so several remote players can each connect and control their
+    // own snake through this one running binary
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|a| a.as_str()) == Some("--serve-ssh") {
+        let bind_addr = args.get(2).map(|a| a.as_str()).unwrap_or(SSH_BIND_ADDR);
+        let game_addr = choose_server(&discover_servers());
+        ssh_server::serve(bind_addr, game_addr).await;
+        return;
+    }
+
+    // `--plain` sends each turn as a plain-text board redraw instead of ANSI cursor
+    // writes, so the game stays playable over a line-oriented client like `nc`
+    let mut game = game::Game::empty();
+    if args.iter().any(|a| a == "--plain") {
+        game.set_plain_mode(true);
+    }
+
+    // Discover servers on the LAN and let the user pick one
+    let servers = discover_servers();
+    let addr = choose_server(&servers);
+    play(&addr, game).await;
+}
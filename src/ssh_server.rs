@@ -0,0 +1,107 @@
+//! Hosts the snake client behind an SSH server instead of a local terminal.
+//!
+//! Each accepted channel gets its own `Game<NetworkBackend, ChannelInput>` connected to
+//! the snake game server, so one running binary can seat several remote players at
+//! once, each controlling their own snake and seeing only their own board.
+use crate::backend::{ChannelInput, NetworkBackend};
+use crate::game;
+use crate::play;
+use rand::rng;
+use russh::keys::{Algorithm, PrivateKey};
+use russh::server::{Auth, Config, Handle, Handler, Msg, Server as _, Session};
+use russh::{Channel, ChannelId};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// Bind `bind_addr` and accept SSH sessions, each playing against the game server at
+/// `game_addr` on its own board
+pub async fn serve(bind_addr: &str, game_addr: String) {
+    let config = Arc::new(Config {
+        keys: vec![PrivateKey::random(&mut rng(), Algorithm::Ed25519).unwrap()],
+        ..Default::default()
+    });
+
+    let socket = TcpListener::bind(bind_addr).await.unwrap();
+    let mut server = SnakeSshServer { game_addr };
+    server.run_on_socket(config, &socket).await.unwrap();
+}
+
+#[derive(Clone)]
+struct SnakeSshServer {
+    game_addr: String,
+}
+impl russh::server::Server for SnakeSshServer {
+    type Handler = SnakeSession;
+
+    fn new_client(&mut self, _peer_addr: Option<std::net::SocketAddr>) -> SnakeSession {
+        SnakeSession {
+            game_addr: self.game_addr.clone(),
+            input: ChannelInput::new(),
+        }
+    }
+}
+
+struct SnakeSession {
+    game_addr: String,
+    input: ChannelInput,
+}
+impl Handler for SnakeSession {
+    type Error = russh::Error;
+
+    // This is a toy client with no accounts of its own, so any user is let straight in
+    async fn auth_none(&mut self, _user: &str) -> Result<Auth, Self::Error> {
+        Ok(Auth::Accept)
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        reply: russh::server::ChannelOpenHandle,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        reply.accept().await;
+
+        let channel_id = channel.id();
+        let handle = session.handle();
+        let game_addr = self.game_addr.clone();
+        let input = self.input.clone();
+
+        // NetworkBackend ships frames down a plain std::sync::mpsc channel (it's
+        // written to synchronously from inside `Game`'s render calls); bridge those
+        // onto the session's async handle on a dedicated task
+        let (frames_tx, frames_rx) = std::sync::mpsc::channel::<Vec<u8>>();
+        tokio::spawn(forward_frames(handle, channel_id, frames_rx));
+
+        let backend = NetworkBackend::new(frames_tx);
+        let game = game::Game::with_input(backend, input);
+        tokio::spawn(async move {
+            play(&game_addr, game).await;
+        });
+
+        Ok(())
+    }
+
+    async fn data(&mut self, _channel: ChannelId, data: &[u8], _session: &mut Session) -> Result<(), Self::Error> {
+        self.input.push(data);
+        Ok(())
+    }
+}
+
+/// Drain `frames_rx` (fed synchronously from `NetworkBackend::flush`) and ship every
+/// frame down the SSH channel as it arrives
+async fn forward_frames(handle: Handle, channel_id: ChannelId, frames_rx: std::sync::mpsc::Receiver<Vec<u8>>) {
+    let (bridge_tx, mut bridge_rx) = mpsc::channel::<Vec<u8>>(32);
+    tokio::task::spawn_blocking(move || {
+        while let Ok(frame) = frames_rx.recv() {
+            if bridge_tx.blocking_send(frame).is_err() {
+                break;
+            }
+        }
+    });
+    while let Some(frame) = bridge_rx.recv().await {
+        if handle.data(channel_id, frame).await.is_err() {
+            break;
+        }
+    }
+}